@@ -1,12 +1,20 @@
 use argh::FromArgs;
+use hmac::{Hmac, Mac};
 use indicatif::{ProgressBar, ProgressStyle};
 use memmap::MmapOptions;
+use rand::RngCore;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::Sha256;
 use std::{collections::HashMap, fs::OpenOptions, io::SeekFrom};
 use std::fmt;
+use std::hash::Hasher;
+use std::io::Read;
 use std::io::Seek;
+use std::io::Write;
 use std::fs::File;
+use std::str::FromStr;
 use std::{
     error::Error,
     fmt::Debug,
@@ -14,23 +22,103 @@ use std::{
     time::Instant,
     write,
 };
+use twox_hash::XxHash64;
 
-const HASH_LENGTH: usize = 16;
+/// A pluggable hash function, shared by the cracking and account paths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum HashAlgo {
+    Md5,
+    Sha1,
+    Sha256,
+    XxHash64,
+}
+
+impl HashAlgo {
+    fn digest_len(self) -> usize {
+        match self {
+            HashAlgo::Md5 => 16,
+            HashAlgo::Sha1 => 20,
+            HashAlgo::Sha256 => 32,
+            HashAlgo::XxHash64 => 8,
+        }
+    }
+
+    /// Hashes `input` into `out`, which must be exactly `self.digest_len()` bytes.
+    fn hash_into(self, input: &[u8], out: &mut [u8]) {
+        match self {
+            HashAlgo::Md5 => out.copy_from_slice(&md5::compute(input).0),
+            HashAlgo::Sha1 => {
+                use sha1::Digest;
+                out.copy_from_slice(&Sha1::digest(input));
+            }
+            HashAlgo::Sha256 => {
+                use sha2::Digest;
+                out.copy_from_slice(&Sha256::digest(input));
+            }
+            HashAlgo::XxHash64 => {
+                // Written against the `std::hash::Hasher` impl rather than the
+                // `oneshot` associated function, since that's only on
+                // twox-hash's 2.x rewrite and we have no Cargo.toml to pin a
+                // major version against.
+                let mut hasher = XxHash64::with_seed(0);
+                hasher.write(input);
+                out.copy_from_slice(&hasher.finish().to_le_bytes());
+            }
+        }
+    }
+}
+
+impl FromStr for HashAlgo {
+    type Err = String;
 
-/// Maps username to passwords
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "md5" => Ok(HashAlgo::Md5),
+            "sha1" => Ok(HashAlgo::Sha1),
+            "sha256" => Ok(HashAlgo::Sha256),
+            "xxhash64" | "xxh64" => Ok(HashAlgo::XxHash64),
+            other => Err(format!("unknown hash algorithm: {}", other)),
+        }
+    }
+}
+
+/// Maps username to stored credential hashes
 #[derive(Clone, Default, Serialize, Deserialize)]
 struct Database {
-    records: HashMap<String, Vec<u8>>,
+    records: HashMap<String, StoredHash>,
+}
+
+/// A stored credential. `Unsalted` exists so the rainbow/hash-table demos
+/// have something to crack, via `add-user --no-salt`.
+#[derive(Clone, Serialize, Deserialize)]
+enum StoredHash {
+    Unsalted { algo: HashAlgo, digest: Vec<u8> },
+    Salted { salt: [u8; 16], hmac: Vec<u8> },
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn hmac_sha256(salt: &[u8], password: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(salt).expect("HMAC accepts a key of any length");
+    mac.update(password);
+    mac.finalize().into_bytes().to_vec()
 }
 
 impl Database {
     const PATH: &'static str = "users.db";
 
     fn load_or_create() -> Result<Self, Box<dyn Error>> {
-        Ok(match File::open(Self::PATH) {
-            // new: snap usage
-            Ok(f) => bincode::deserialize_from(snap::read::FrameDecoder::new(f))?,
-            Err(_) => Default::default(),
+        let file = match File::open(Self::PATH) {
+            Ok(f) => f,
+            Err(_) => return Ok(Default::default()),
+        };
+        // new: snap usage
+        bincode::deserialize_from(snap::read::FrameDecoder::new(file)).map_err(|e| {
+            format!(
+                "users.db doesn't match this build's record format ({}); delete it and recreate accounts with add-user",
+                e
+            )
+            .into()
         })
     }
 
@@ -119,6 +207,9 @@ enum Command {
     Bruteforce(Bruteforce),
     GenHtable(GenHtable),
     UseHtable(UseHtable),
+    GenRtable(GenRtable),
+    UseRtable(UseRtable),
+    Upgrade(Upgrade),
 }
 
 #[derive(FromArgs)]
@@ -129,12 +220,51 @@ struct UseHtable {}
 #[derive(FromArgs)]
 /// Generate a hash table
 #[argh(subcommand, name = "gen-htable")]
-struct GenHtable {}
+struct GenHtable {
+    /// hash algorithm to fill the table with (md5, sha1, sha256, xxhash64)
+    #[argh(option, default = "HashAlgo::Md5")]
+    hash: HashAlgo,
+}
+
+#[derive(FromArgs)]
+/// Use a rainbow table
+#[argh(subcommand, name = "use-rtable")]
+struct UseRtable {}
+
+#[derive(FromArgs)]
+/// Generate a rainbow table (Oechslin-style chained reduction)
+#[argh(subcommand, name = "gen-rtable")]
+struct GenRtable {
+    /// hash algorithm to build the chains with (md5, sha1, sha256, xxhash64)
+    #[argh(option, default = "HashAlgo::Md5")]
+    hash: HashAlgo,
+}
+
+#[derive(FromArgs)]
+/// Rewrite an older table.db into the current versioned, checksummed format
+#[argh(subcommand, name = "upgrade")]
+struct Upgrade {
+    /// item length to assume if table.db predates any header
+    #[argh(option, default = "6")]
+    len: u32,
+
+    /// charset to assume if table.db predates any header
+    #[argh(option, default = "String::from(\"abcdefghijklmnopqrstuvwxyz0123456789\")")]
+    charset: String,
+
+    /// hash algorithm the existing hashes were computed with, if table.db predates any header
+    #[argh(option, default = "HashAlgo::Md5")]
+    hash: HashAlgo,
+}
 
 #[derive(FromArgs)]
 /// Try to brute-force user accounts
 #[argh(subcommand, name = "bruteforce")]
-struct Bruteforce {}
+struct Bruteforce {
+    /// hash algorithm to attack with (md5, sha1, sha256, xxhash64)
+    #[argh(option, default = "HashAlgo::Md5")]
+    hash: HashAlgo,
+}
 
 #[derive(FromArgs)]
 /// Add a user to the database
@@ -145,6 +275,16 @@ struct AddUser {
 
     #[argh(positional)]
     password: String,
+
+    /// hash algorithm to store the password with when `--no-salt` is passed
+    /// (md5, sha1, sha256, xxhash64)
+    #[argh(option, default = "HashAlgo::Md5")]
+    hash: HashAlgo,
+
+    /// store a bare hash instead of a salted HMAC. Insecure: only useful to
+    /// set up an account that gen-htable/gen-rtable can actually crack.
+    #[argh(switch)]
+    no_salt: bool,
 }
 
 #[derive(FromArgs)]
@@ -168,36 +308,59 @@ struct BruteforceParams {
     charset: Charset,
 }
 
-fn bruteforce() -> Result<(), Box<dyn Error>> {
+fn bruteforce(hash_algo: HashAlgo) -> Result<(), Box<dyn Error>> {
     let params = BruteforceParams {
         len_range: 4..=8,
         charset: "abcdefghijklmnopqrstuvwxyz0123456789".into(),
     };
-    println!("{:?}", params);
+    println!("{:?} hashed with {:?}", params, hash_algo);
 
     let records = Database::with(|db| Ok(db.records.clone()))?;
+    if records.values().any(|r| matches!(r, StoredHash::Salted { .. })) {
+        println!(
+            "note: salted accounts get no speedup from a shared digest here, \
+             each candidate is re-hashed with the user's own salt"
+        );
+    }
     let start_time = Instant::now();
+    let digest_len = hash_algo.digest_len();
 
     for len in params.len_range.clone() {
         params
             .charset
             .range(len as _)
             .into_par_iter()
-            .for_each_with(vec![0u8; len], |mut buf, i| {
-                params.charset.get_into(i, &mut buf);
-                let hash = md5::compute(&buf);
+            .for_each_with(
+                (vec![0u8; len], vec![0u8; digest_len]),
+                |(buf, hash), i| {
+                    params.charset.get_into(i, buf);
+                    hash_algo.hash_into(buf, hash);
 
-                for (db_user, db_hash) in &records {
-                    if hash.as_ref() == db_hash {
-                        println!(
-                            "[CRACKED in {:?}] user ({}) has password ({})",
-                            start_time.elapsed(),
-                            db_user,
-                            std::str::from_utf8(&buf).unwrap_or("<not utf-8>")
-                        );
+                    for (db_user, stored) in &records {
+                        let cracked = match stored {
+                            StoredHash::Unsalted { algo, digest } => {
+                                *algo == hash_algo && digest == hash
+                            }
+                            StoredHash::Salted { salt, hmac } => {
+                                HmacSha256::new_from_slice(salt)
+                                    .expect("HMAC accepts a key of any length")
+                                    .chain_update(buf.as_slice())
+                                    .verify_slice(hmac)
+                                    .is_ok()
+                            }
+                        };
+
+                        if cracked {
+                            println!(
+                                "[CRACKED in {:?}] user ({}) has password ({})",
+                                start_time.elapsed(),
+                                db_user,
+                                std::str::from_utf8(buf).unwrap_or("<not utf-8>")
+                            );
+                        }
                     }
-                }
-            })
+                },
+            )
     }
     Ok(())
 }
@@ -206,6 +369,106 @@ fn bruteforce() -> Result<(), Box<dyn Error>> {
 struct TableHeader {
     len: u32,
     charset: Vec<u8>,
+    hash_algo: HashAlgo,
+}
+
+/// Marks the start of `table.db`, ahead of the bincode-encoded `TableHeader`.
+const TABLE_MAGIC: &[u8; 4] = b"PFHT";
+/// Version 1 had no length prefix or checksum on the header; `upgrade_table`
+/// migrates it forward.
+const TABLE_FORMAT_VERSION: u16 = 2;
+
+/// `table.db` is chunked into pieces of at most this many bytes, both for
+/// writing and for `use_htable`'s per-chunk CRC32 integrity check.
+const MAX_CHUNK_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+
+fn chunk_layout(total_hashes: u64, hash_length: usize) -> (u64, u64, u64) {
+    let hashes_per_chunk = MAX_CHUNK_BYTES / hash_length as u64;
+    let bytes_per_chunk = hashes_per_chunk * hash_length as u64;
+    // Round up so the trailing partial chunk isn't silently dropped.
+    let num_chunks = (total_hashes + hashes_per_chunk - 1) / hashes_per_chunk;
+    (hashes_per_chunk, bytes_per_chunk, num_chunks)
+}
+
+/// Writes magic bytes, format version, and a checksummed `TableHeader` to
+/// `file`, returning the offset the hash data starts at.
+fn write_table_header(file: &mut File, header: &TableHeader) -> Result<u64, Box<dyn Error>> {
+    let header_bytes = bincode::serialize(header)?;
+    file.write_all(TABLE_MAGIC)?;
+    file.write_all(&TABLE_FORMAT_VERSION.to_le_bytes())?;
+    file.write_all(&(header_bytes.len() as u64).to_le_bytes())?;
+    file.write_all(&header_bytes)?;
+    file.write_all(&crc32fast::hash(&header_bytes).to_le_bytes())?;
+    Ok(file.stream_position()?)
+}
+
+/// Validates the magic bytes, format version, and header checksum, then
+/// decodes the `TableHeader`, returning it with the offset the hash data
+/// starts at.
+fn read_table_header(file: &mut File) -> Result<(TableHeader, u64), Box<dyn Error>> {
+    let mut magic = [0u8; 4];
+    file.read_exact(&mut magic)?;
+    if &magic != TABLE_MAGIC {
+        return Err(format!(
+            "table.db has no recognizable header (expected magic {:?}, found {:?}); run the `upgrade` subcommand first",
+            TABLE_MAGIC, magic
+        )
+        .into());
+    }
+
+    let mut version_bytes = [0u8; 2];
+    file.read_exact(&mut version_bytes)?;
+    let version = u16::from_le_bytes(version_bytes);
+    if version != TABLE_FORMAT_VERSION {
+        return Err(format!(
+            "table.db is format version {}, this build only understands version {}; run the `upgrade` subcommand",
+            version, TABLE_FORMAT_VERSION
+        )
+        .into());
+    }
+
+    let mut header_len_bytes = [0u8; 8];
+    file.read_exact(&mut header_len_bytes)?;
+    let header_len = u64::from_le_bytes(header_len_bytes);
+
+    let mut header_bytes = vec![0u8; header_len as usize];
+    file.read_exact(&mut header_bytes)?;
+
+    let mut checksum_bytes = [0u8; 4];
+    file.read_exact(&mut checksum_bytes)?;
+    let stored_checksum = u32::from_le_bytes(checksum_bytes);
+    if crc32fast::hash(&header_bytes) != stored_checksum {
+        return Err(
+            "table.db's header is corrupt (checksum mismatch); regenerate it with gen-htable or run the `upgrade` subcommand"
+                .into(),
+        );
+    }
+
+    let header: TableHeader = bincode::deserialize(&header_bytes)?;
+    let offset = file.stream_position()?;
+    Ok((header, offset))
+}
+
+/// Appends a bincode-encoded checksum list, followed by an 8-byte
+/// little-endian trailer giving the footer's own offset.
+fn write_checksum_footer(file: &mut File, checksums: &[u32]) -> Result<(), Box<dyn Error>> {
+    let footer_offset = file.seek(SeekFrom::End(0))?;
+    bincode::serialize_into(&mut *file, &checksums.to_vec())?;
+    file.write_all(&footer_offset.to_le_bytes())?;
+    Ok(())
+}
+
+fn read_checksum_footer(file: &mut File) -> Result<Vec<u32>, Box<dyn Error>> {
+    let file_len = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::End(-8))?;
+    let mut offset_bytes = [0u8; 8];
+    file.read_exact(&mut offset_bytes)?;
+    let footer_offset = u64::from_le_bytes(offset_bytes);
+    if footer_offset >= file_len {
+        return Err("table.db's checksum footer offset is corrupt".into());
+    }
+    file.seek(SeekFrom::Start(footer_offset))?;
+    Ok(bincode::deserialize_from(&mut *file)?)
 }
 
 fn progress_style() -> ProgressStyle {
@@ -214,131 +477,686 @@ fn progress_style() -> ProgressStyle {
         .progress_chars("#>-")
 }
 
-fn gen_htable() -> Result<(), Box<dyn Error>> {
-    let item_len = 6;
-    let charset: Charset = "abcdefghijklmnopqrstuvwxyz0123456789".into();
+/// Sidecar checkpoint for an in-progress `gen_htable` run.
+#[derive(Serialize, Deserialize)]
+struct GenProgress {
+    len: u32,
+    charset: Vec<u8>,
+    hash_algo: HashAlgo,
+    chunk_checksums: Vec<u32>,
+}
+
+const PROGRESS_PATH: &str = "table.db.progress";
+
+fn load_matching_progress(len: u32, charset: &Charset, hash_algo: HashAlgo) -> Option<GenProgress> {
+    let file = File::open(PROGRESS_PATH).ok()?;
+    let progress: GenProgress = bincode::deserialize_from(file).ok()?;
+    if progress.len == len && progress.charset == charset.0 && progress.hash_algo == hash_algo {
+        Some(progress)
+    } else {
+        None
+    }
+}
+
+fn save_progress(progress: &GenProgress) -> Result<(), Box<dyn Error>> {
+    let file = File::create(PROGRESS_PATH)?;
+    bincode::serialize_into(file, progress)?;
+    Ok(())
+}
+
+fn gen_htable(hash_algo: HashAlgo) -> Result<(), Box<dyn Error>> {
+    gen_htable_for(
+        hash_algo,
+        6,
+        "abcdefghijklmnopqrstuvwxyz0123456789".into(),
+    )
+}
+
+/// The body of `gen_htable`, pulled out so tests can drive it against a
+/// tiny keyspace instead of the real multi-billion-hash one.
+fn gen_htable_for(
+    hash_algo: HashAlgo,
+    item_len: u32,
+    charset: Charset,
+) -> Result<(), Box<dyn Error>> {
     let total_hashes = charset.range(item_len).end;
+    let hash_length = hash_algo.digest_len();
     println!(
-        "Generating {} hashes â€” for all items of length {}, with characters {:?}",
-        total_hashes, item_len, charset
+        "Generating {} hashes â€” for all items of length {}, with characters {:?}, hashed with {:?}",
+        total_hashes, item_len, charset, hash_algo
     );
 
-    let progress = ProgressBar::new(total_hashes).with_style(progress_style());
-    progress.enable_steady_tick(250);
+    let (hashes_per_chunk, bytes_per_chunk, num_chunks) = chunk_layout(total_hashes, hash_length);
+
+    // Resume a previous run if its sidecar checkpoint matches the keyspace
+    // and hash we're about to generate; otherwise start a fresh table.
+    let mut checkpoint = load_matching_progress(item_len, &charset, hash_algo)
+        .unwrap_or_else(|| GenProgress {
+            len: item_len,
+            charset: charset.0.to_vec(),
+            hash_algo,
+            chunk_checksums: Vec::with_capacity(num_chunks as usize),
+        });
+    let start_chunk = checkpoint.chunk_checksums.len() as u64;
+    let resuming = start_chunk > 0;
 
-    // Write the header and pre-size the file
-    let hashes_offset_in_file = {
+    if resuming {
+        println!(
+            "Resuming from checkpoint: {}/{} chunks already written",
+            start_chunk, num_chunks
+        );
+    }
+
+    let hashes_offset_in_file = if resuming {
+        let mut file = OpenOptions::new().read(true).open("table.db")?;
+        let (existing_header, hashes_offset_in_file) = read_table_header(&mut file)?;
+        if existing_header.len != item_len
+            || existing_header.charset != charset.0.to_vec()
+            || existing_header.hash_algo != hash_algo
+        {
+            return Err(
+                "table.db.progress does not match table.db's header; remove both files and start over"
+                    .into(),
+            );
+        }
+        hashes_offset_in_file
+    } else {
         let mut file = File::create("table.db")?;
-        bincode::serialize_into(
+        let hashes_offset_in_file = write_table_header(
             &mut file,
             &TableHeader {
                 len: item_len,
                 charset: charset.0.to_vec(),
+                hash_algo,
             },
         )?;
 
-        let hashes_offset_in_file = file.seek(SeekFrom::Current(0))?;
-        let hashes_len = total_hashes * HASH_LENGTH as u64;
-
+        let hashes_len = total_hashes * hash_length as u64;
         let file_len = hashes_offset_in_file + hashes_len;
         file.set_len(file_len)?;
 
         hashes_offset_in_file
     };
 
-    let max_bytes_per_chunk = {
-        let gb: u64 = 1024 * 1024 * 1024;
-        // Picked to keep memory usage low-enough and flush to disk often-enough
-        2 * gb
-    };
-    let hashes_per_chunk = max_bytes_per_chunk / HASH_LENGTH as u64;
-    let bytes_per_chunk = hashes_per_chunk * HASH_LENGTH as u64;
-    let num_chunks = total_hashes / hashes_per_chunk;
+    let progress = ProgressBar::new(total_hashes).with_style(progress_style());
+    progress.enable_steady_tick(250);
+    progress.set_position(start_chunk * hashes_per_chunk);
 
     // For each chunk, one by one...
-    for chunk_index in 0..num_chunks {
+    for chunk_index in start_chunk..num_chunks {
         // Show progress
         let hashes_done = chunk_index * hashes_per_chunk;
         progress.set_position(hashes_done);
 
+        // The final chunk is shorter than the rest when the keyspace
+        // doesn't divide evenly by `hashes_per_chunk`.
+        let hashes_in_chunk = hashes_per_chunk.min(total_hashes - hashes_done);
+        let bytes_in_chunk = hashes_in_chunk * hash_length as u64;
+
         let file = OpenOptions::new().read(true).write(true).open("table.db")?;
         let chunk_offset_in_file = hashes_offset_in_file + chunk_index * bytes_per_chunk;
-        let mut file = unsafe {
+        let mut mmap = unsafe {
             MmapOptions::new()
                 .offset(chunk_offset_in_file)
-                .len(bytes_per_chunk as _)
+                .len(bytes_in_chunk as _)
                 .map_mut(&file)
         }?;
 
-        // Map `hashes_per_chunk` hashes into memory, so we can write to the file
-        let hashes = unsafe {
-            std::slice::from_raw_parts_mut(
-                file.as_mut_ptr() as *mut [u8; HASH_LENGTH],
-                hashes_per_chunk as _,
-            )
-        };
-
         // In the collection of "all outputs of this charset", this is
         // where our chunk starts.
-        let first_item_index = chunk_index * hashes_per_chunk;
+        let first_item_index = hashes_done;
 
-        // Enumerate gives us the position within the chunk.
-        hashes.par_iter_mut().enumerate().for_each_with(
+        // Each hash occupies a `hash_length`-byte stride rather than a
+        // fixed-size array, since the table is self-describing via
+        // `TableHeader::hash_algo` and `hash_length` is only known at runtime.
+        mmap.par_chunks_mut(hash_length).enumerate().for_each_with(
             vec![0u8; item_len as usize],
             |buf, (index_in_chunk, out)| {
                 let item_index = first_item_index + index_in_chunk as u64;
                 // Generate the candidate password
                 charset.get_into(item_index, buf);
                 // Hash it and store it to the file.
-                *out = md5::compute(buf).0;
+                hash_algo.hash_into(buf, out);
             },
         );
+
+        // Checksum the chunk now, while it's still mapped, so a later
+        // `use_htable` can tell a corrupt or partially-written chunk apart
+        // from a merely slow-to-generate one.
+        checkpoint.chunk_checksums.push(crc32fast::hash(&mmap));
+        drop(mmap);
+
+        // Checkpoint after every chunk so a killed/interrupted run can pick
+        // back up from the last fully-written chunk instead of restarting.
+        save_progress(&checkpoint)?;
     }
 
     progress.finish();
+
+    // If a previous run already wrote the footer but was killed before it
+    // could remove the progress file, resuming here would otherwise append
+    // a second, redundant footer on top of the first.
+    let footer_already_written = {
+        let mut file = File::open("table.db")?;
+        matches!(read_checksum_footer(&mut file), Ok(existing) if existing.len() as u64 == num_chunks)
+    };
+
+    if !footer_already_written {
+        let mut file = OpenOptions::new().write(true).open("table.db")?;
+        write_checksum_footer(&mut file, &checkpoint.chunk_checksums)?;
+    }
+    std::fs::remove_file(PROGRESS_PATH).ok();
+
     Ok(())
 }
 
+/// Shared by `use_htable`/`use_rtable`; `structure` names whichever one the
+/// caller is using ("table" or "chains").
+fn note_salted_accounts(count: usize, structure: &str) {
+    if count > 0 {
+        println!(
+            "{} salted account(s) found; the {} can't crack these, \
+             re-deriving the keyspace live against each salt instead \
+             (this is exactly why salting defeats precomputed tables)",
+            count, structure
+        );
+    }
+}
+
+/// Regenerates the keyspace live and checks each candidate against the
+/// user's own salt, since no precomputed table can help here.
+fn crack_salted(charset: &Charset, len: u32, salt: &[u8; 16], hmac: &[u8]) -> Option<String> {
+    charset.range(len).into_par_iter().find_map_any(|item_index| {
+        let mut buf = vec![0u8; len as usize];
+        charset.get_into(item_index, &mut buf);
+        let matches = HmacSha256::new_from_slice(salt)
+            .expect("HMAC accepts a key of any length")
+            .chain_update(buf.as_slice())
+            .verify_slice(hmac)
+            .is_ok();
+        matches.then(|| std::str::from_utf8(&buf).unwrap_or("<not utf-8>").to_string())
+    })
+}
+
 fn use_htable() -> Result<(), Box<dyn Error>> {
     let (header, hashes_offset_in_file) = {
         let mut file = File::open("table.db")?;
-        let header: TableHeader = bincode::deserialize_from(&mut file)?;
-        let offset = file.seek(SeekFrom::Current(0))?;
-        (header, offset)
+        read_table_header(&mut file)?
     };
 
+    let hash_length = header.hash_algo.digest_len();
     let charset = Charset(header.charset);
-    let num_hashes = charset.range(header.len).end;
+    let total_hashes = charset.range(header.len).end;
+    let (hashes_per_chunk, bytes_per_chunk, num_chunks) = chunk_layout(total_hashes, hash_length);
+
+    // Verify every chunk's CRC32 before trusting the mmap below, so a
+    // corrupt or partially-written table.db fails loudly instead of handing
+    // back garbage "cracked" passwords.
+    {
+        let mut file = File::open("table.db")?;
+        let checksums = read_checksum_footer(&mut file)?;
+        if checksums.len() as u64 != num_chunks {
+            return Err(format!(
+                "table.db's checksum footer has {} entries, expected {} — the file looks truncated or corrupt",
+                checksums.len(),
+                num_chunks
+            )
+            .into());
+        }
+
+        let check_file = File::open("table.db")?;
+        for (chunk_index, &expected) in checksums.iter().enumerate() {
+            let chunk_index = chunk_index as u64;
+            let hashes_done = chunk_index * hashes_per_chunk;
+            let hashes_in_chunk = hashes_per_chunk.min(total_hashes - hashes_done);
+            let bytes_in_chunk = hashes_in_chunk * hash_length as u64;
+            let chunk_offset_in_file = hashes_offset_in_file + chunk_index * bytes_per_chunk;
+            let mmap = unsafe {
+                MmapOptions::new()
+                    .offset(chunk_offset_in_file)
+                    .len(bytes_in_chunk as _)
+                    .map(&check_file)
+            }?;
+            let actual = crc32fast::hash(&mmap);
+            if actual != expected {
+                return Err(format!(
+                    "table.db chunk {} failed its checksum (corrupt or partial write)",
+                    chunk_index
+                )
+                .into());
+            }
+        }
+    }
 
     let file = File::open("table.db")?;
-    let file = unsafe { MmapOptions::new().offset(hashes_offset_in_file).map(&file) }?;
-    let hashes = unsafe {
-        std::slice::from_raw_parts(
-            file.as_ptr() as *const [u8; HASH_LENGTH],
-            num_hashes as usize,
-        )
-    };
+    let mmap = unsafe { MmapOptions::new().offset(hashes_offset_in_file).map(&file) }?;
 
     let records = Database::with(|f| Ok(f.records.clone()))?;
     let start_time = Instant::now();
 
-    hashes.par_iter().enumerate().for_each_with(
+    let (unsalted, salted): (Vec<_>, Vec<_>) = records
+        .into_iter()
+        .partition(|(_, stored)| matches!(stored, StoredHash::Unsalted { .. }));
+
+    note_salted_accounts(salted.len(), "table");
+
+    mmap.par_chunks(hash_length).enumerate().for_each_with(
         vec![0u8; header.len as usize],
         |buf, (item_index, hash)| {
-            for (db_user, db_hash) in &records {
-                if db_hash == hash {
-                    charset.get_into(item_index as _, buf);
+            for (db_user, stored) in &unsalted {
+                if let StoredHash::Unsalted { algo, digest } = stored {
+                    if *algo == header.hash_algo && digest == hash {
+                        charset.get_into(item_index as _, buf);
+                        println!(
+                            "[CRACKED in {:?}] user {} has password {}",
+                            start_time.elapsed(),
+                            db_user,
+                            std::str::from_utf8(buf).unwrap_or("<not utf-8>")
+                        );
+                    }
+                }
+            }
+        },
+    );
+
+    for (db_user, stored) in &salted {
+        if let StoredHash::Salted { salt, hmac } = stored {
+            if let Some(password) = crack_salted(&charset, header.len, salt, hmac) {
+                println!(
+                    "[CRACKED in {:?}] user {} has password {} (salted; the table didn't help)",
+                    start_time.elapsed(),
+                    db_user,
+                    password
+                );
+            }
+        }
+    }
+
+    println!("Spent {:?} going through whole table", start_time.elapsed());
+
+    Ok(())
+}
+
+/// Parses the version 1 on-disk layout: magic + version + a bare bincode
+/// `TableHeader`, with no length prefix or checksum. Used only by `upgrade_table`.
+fn read_legacy_v1_header(file: &mut File) -> Result<(TableHeader, u64), Box<dyn Error>> {
+    let mut magic = [0u8; 4];
+    file.read_exact(&mut magic)?;
+    if &magic != TABLE_MAGIC {
+        return Err("not a magic-bearing table.db".into());
+    }
+
+    let mut version_bytes = [0u8; 2];
+    file.read_exact(&mut version_bytes)?;
+    if u16::from_le_bytes(version_bytes) != 1 {
+        return Err("not a version 1 table.db".into());
+    }
+
+    let header: TableHeader = bincode::deserialize_from(&mut *file)?;
+    let offset = file.stream_position()?;
+    Ok((header, offset))
+}
+
+/// Rewrites `table.db` into the current magic/version/checksum layout.
+/// Handles a current-format file (no-op), a version 1 file, and a headerless
+/// one (`args` supplies the len/charset/hash for the latter).
+fn upgrade_table(args: Upgrade) -> Result<(), Box<dyn Error>> {
+    {
+        let mut file = File::open("table.db")?;
+        if read_table_header(&mut file).is_ok() {
+            println!("table.db is already in the current versioned format, nothing to do");
+            return Ok(());
+        }
+    }
+
+    let legacy_v1_header: Option<(TableHeader, u64)> = {
+        let mut file = File::open("table.db")?;
+        read_legacy_v1_header(&mut file).ok()
+    };
+
+    let legacy_header: Option<(TableHeader, u64)> = match legacy_v1_header {
+        Some(found) => Some(found),
+        None => {
+            let mut file = File::open("table.db")?;
+            let decoded: Result<TableHeader, _> = bincode::deserialize_from(&mut file);
+            decoded
+                .ok()
+                .map(|header| (header, file.stream_position().unwrap_or(0)))
+        }
+    };
+
+    let (len, charset_bytes, hash_algo, hashes_offset_in_old_file) = match legacy_header {
+        Some((header, offset)) => {
+            println!(
+                "Found a pre-current-format table.db header: len={}, hash={:?}",
+                header.len, header.hash_algo
+            );
+            (header.len, header.charset, header.hash_algo, offset)
+        }
+        None => {
+            println!(
+                "table.db has no recognizable header at all; assuming len={}, charset={:?}, hash={:?}",
+                args.len, args.charset, args.hash
+            );
+            (args.len, args.charset.into_bytes(), args.hash, 0)
+        }
+    };
+
+    let charset = Charset(charset_bytes);
+    let hash_length = hash_algo.digest_len();
+    let total_hashes = charset.range(len).end;
+    let (hashes_per_chunk, bytes_per_chunk, num_chunks) = chunk_layout(total_hashes, hash_length);
+
+    let old_file = File::open("table.db")?;
+    let new_path = "table.db.upgraded";
+    let hashes_offset_in_new_file = {
+        let mut new_file = File::create(new_path)?;
+        let offset = write_table_header(
+            &mut new_file,
+            &TableHeader {
+                len,
+                charset: charset.0.to_vec(),
+                hash_algo,
+            },
+        )?;
+        new_file.set_len(offset + total_hashes * hash_length as u64)?;
+        offset
+    };
+
+    let progress = ProgressBar::new(num_chunks).with_style(progress_style());
+    let mut chunk_checksums = Vec::with_capacity(num_chunks as usize);
+
+    for chunk_index in 0..num_chunks {
+        progress.set_position(chunk_index);
+        let hashes_done = chunk_index * hashes_per_chunk;
+        let hashes_in_chunk = hashes_per_chunk.min(total_hashes - hashes_done);
+        let bytes_in_chunk = hashes_in_chunk * hash_length as u64;
+        let chunk_offset = chunk_index * bytes_per_chunk;
+
+        let old_mmap = unsafe {
+            MmapOptions::new()
+                .offset(hashes_offset_in_old_file + chunk_offset)
+                .len(bytes_in_chunk as _)
+                .map(&old_file)
+        }?;
+
+        let new_file = OpenOptions::new().read(true).write(true).open(new_path)?;
+        let mut new_mmap = unsafe {
+            MmapOptions::new()
+                .offset(hashes_offset_in_new_file + chunk_offset)
+                .len(bytes_in_chunk as _)
+                .map_mut(&new_file)
+        }?;
+        new_mmap.copy_from_slice(&old_mmap);
+        chunk_checksums.push(crc32fast::hash(&new_mmap));
+    }
+    progress.finish();
+
+    let mut new_file = OpenOptions::new().write(true).open(new_path)?;
+    write_checksum_footer(&mut new_file, &chunk_checksums)?;
+    drop(new_file);
+
+    std::fs::rename(new_path, "table.db")?;
+    println!("Rewrote table.db in the current versioned, checksummed format");
+
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize)]
+struct RainbowTableHeader {
+    len: u32,
+    charset: Vec<u8>,
+    /// Number of reduction steps per chain, `t` in Oechslin's notation.
+    chain_len: u64,
+    /// Number of chains, `m` in Oechslin's notation.
+    num_chains: u64,
+    hash_algo: HashAlgo,
+}
+
+/// One rainbow chain, identified only by its two endpoints. Everything in
+/// between is regenerated on demand during a lookup.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct Chain {
+    start_index: u64,
+    endpoint_index: u64,
+}
+
+/// Position-dependent reduction function `R_i`: turns a hash's first 8 bytes
+/// into a plaintext index in `0..keyspace_size`.
+fn reduce(hash: &[u8], position: u64, keyspace_size: u64) -> u64 {
+    let mut first_8 = [0u8; 8];
+    first_8.copy_from_slice(&hash[..8]);
+    u64::from_le_bytes(first_8).wrapping_add(position) % keyspace_size
+}
+
+/// Generates `num_chains` Oechslin-style chains of length `chain_len`,
+/// sorted by endpoint so callers can binary-search. Pulled out of
+/// `gen_rtable` so the chain math is testable without touching disk;
+/// `on_batch` reports the running total after each batch.
+fn generate_chains(
+    hash_algo: HashAlgo,
+    charset: &Charset,
+    item_len: u32,
+    keyspace_size: u64,
+    chain_len: u64,
+    num_chains: u64,
+    mut on_batch: impl FnMut(u64),
+) -> Vec<Chain> {
+    let hash_length = hash_algo.digest_len();
+    let chains_per_batch = 100_000u64.min(num_chains.max(1));
+    let mut chains: Vec<Chain> = Vec::with_capacity(num_chains as usize);
+    let mut generated = 0u64;
+
+    while generated < num_chains {
+        let batch_len = chains_per_batch.min(num_chains - generated);
+        let batch_start = generated;
+
+        let mut batch: Vec<Chain> = (0..batch_len)
+            .into_par_iter()
+            .map_with(
+                (vec![0u8; item_len as usize], vec![0u8; hash_length]),
+                |(buf, hash), offset| {
+                    let chain_index = batch_start + offset;
+                    // Spread starting points evenly across the keyspace.
+                    let start_index = chain_index * (keyspace_size / num_chains);
+                    let mut index = start_index;
+
+                    for position in 0..chain_len {
+                        charset.get_into(index, buf);
+                        hash_algo.hash_into(buf, hash);
+                        index = reduce(hash, position, keyspace_size);
+                    }
+
+                    Chain {
+                        start_index,
+                        endpoint_index: index,
+                    }
+                },
+            )
+            .collect();
+        chains.append(&mut batch);
+
+        generated += batch_len;
+        on_batch(generated);
+    }
+
+    // Sorted by endpoint so lookups can binary-search instead of scanning.
+    chains.sort_unstable_by_key(|c| c.endpoint_index);
+    chains
+}
+
+fn gen_rtable(hash_algo: HashAlgo) -> Result<(), Box<dyn Error>> {
+    let item_len = 6;
+    let charset: Charset = "abcdefghijklmnopqrstuvwxyz0123456789".into();
+    let keyspace_size = charset.range(item_len).end;
+
+    // Long chains with fewer starting points trade lookup time (more
+    // reduction steps to try per lookup) for table size; this is the
+    // classic rainbow-table space/time knob.
+    let chain_len: u64 = 4_000;
+    let num_chains: u64 = 2_000_000;
+
+    println!(
+        "Generating {} chains of length {} over {} items of length {}, with characters {:?}, hashed with {:?}",
+        num_chains, chain_len, keyspace_size, item_len, charset, hash_algo
+    );
+
+    let progress = ProgressBar::new(num_chains).with_style(progress_style());
+    progress.enable_steady_tick(250);
+
+    let chains = generate_chains(
+        hash_algo,
+        &charset,
+        item_len,
+        keyspace_size,
+        chain_len,
+        num_chains,
+        |generated| progress.set_position(generated),
+    );
+    progress.finish();
+
+    let mut file = File::create("rtable.db")?;
+    bincode::serialize_into(
+        &mut file,
+        &RainbowTableHeader {
+            len: item_len,
+            charset: charset.0.to_vec(),
+            chain_len,
+            num_chains,
+            hash_algo,
+        },
+    )?;
+    bincode::serialize_into(&mut file, &chains)?;
+
+    Ok(())
+}
+
+/// Fixed, read-only context for every `lookup_rtable` call against one
+/// loaded `rtable.db`, bundled into a struct rather than five loose arguments.
+struct RtableLookupParams<'a> {
+    charset: &'a Charset,
+    keyspace_size: u64,
+    chains: &'a [Chain],
+    chain_len: u64,
+    hash_algo: HashAlgo,
+}
+
+/// Looks up `target_hash` in a sorted rainbow chain table, returning the
+/// matching plaintext if found. Tries every position from the end of the
+/// chain backwards, since the hash could have been produced at any of them.
+fn lookup_rtable(
+    target_hash: &[u8],
+    params: &RtableLookupParams,
+    buf: &mut [u8],
+    hash_buf: &mut [u8],
+) -> Option<String> {
+    let RtableLookupParams {
+        charset,
+        keyspace_size,
+        chains,
+        chain_len,
+        hash_algo,
+    } = *params;
+
+    for position in (0..chain_len).rev() {
+        let mut candidate = reduce(target_hash, position, keyspace_size);
+        for next_position in (position + 1)..chain_len {
+            charset.get_into(candidate, buf);
+            hash_algo.hash_into(buf, hash_buf);
+            candidate = reduce(hash_buf, next_position, keyspace_size);
+        }
+        let endpoint = candidate;
+
+        let Ok(found) = chains.binary_search_by_key(&endpoint, |c| c.endpoint_index) else {
+            continue;
+        };
+
+        // Several chains can share an endpoint; check all of them.
+        let mut lo = found;
+        while lo > 0 && chains[lo - 1].endpoint_index == endpoint {
+            lo -= 1;
+        }
+        let mut hi = found;
+        while hi + 1 < chains.len() && chains[hi + 1].endpoint_index == endpoint {
+            hi += 1;
+        }
+
+        for chain in &chains[lo..=hi] {
+            let mut index = chain.start_index;
+            for step in 0..position {
+                charset.get_into(index, buf);
+                hash_algo.hash_into(buf, hash_buf);
+                index = reduce(hash_buf, step, keyspace_size);
+            }
+            charset.get_into(index, buf);
+            hash_algo.hash_into(buf, hash_buf);
+            if hash_buf == target_hash {
+                return Some(std::str::from_utf8(buf).unwrap_or("<not utf-8>").to_string());
+            }
+            // False alarm from a chain merge; keep searching other chains/positions.
+        }
+    }
+    None
+}
+
+fn use_rtable() -> Result<(), Box<dyn Error>> {
+    let (header, chains): (RainbowTableHeader, Vec<Chain>) = {
+        let mut file = File::open("rtable.db")?;
+        let header: RainbowTableHeader = bincode::deserialize_from(&mut file)?;
+        let chains: Vec<Chain> = bincode::deserialize_from(&mut file)?;
+        (header, chains)
+    };
+
+    let hash_algo = header.hash_algo;
+    let charset = Charset(header.charset);
+    let keyspace_size = charset.range(header.len).end;
+
+    let records = Database::with(|db| Ok(db.records.clone()))?;
+    let salted_count = records
+        .values()
+        .filter(|r| matches!(r, StoredHash::Salted { .. }))
+        .count();
+    note_salted_accounts(salted_count, "chains");
+
+    let start_time = Instant::now();
+    let mut buf = vec![0u8; header.len as usize];
+    let mut hash_buf = vec![0u8; hash_algo.digest_len()];
+    let lookup_params = RtableLookupParams {
+        charset: &charset,
+        keyspace_size,
+        chains: &chains,
+        chain_len: header.chain_len,
+        hash_algo,
+    };
+
+    for (db_user, stored) in &records {
+        match stored {
+            StoredHash::Unsalted { algo, digest } if *algo == hash_algo => {
+                if let Some(password) =
+                    lookup_rtable(digest, &lookup_params, &mut buf, &mut hash_buf)
+                {
                     println!(
                         "[CRACKED in {:?}] user {} has password {}",
                         start_time.elapsed(),
                         db_user,
-                        std::str::from_utf8(buf).unwrap_or("<not utf-8>")
+                        password
                     );
                 }
             }
-        },
-    );
-    println!("Spent {:?} going through whole table", start_time.elapsed());
+            StoredHash::Salted { salt, hmac } => {
+                if let Some(password) = crack_salted(&charset, header.len, salt, hmac) {
+                    println!(
+                        "[CRACKED in {:?}] user {} has password {} (salted; the chains didn't help)",
+                        start_time.elapsed(),
+                        db_user,
+                        password
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+    println!("Spent {:?} going through rainbow table", start_time.elapsed());
 
     Ok(())
 }
@@ -347,10 +1165,28 @@ fn main() -> Result<(), Box<dyn Error>> {
     let args: Args = argh::from_env();
     match args.command {
         Command::AddUser(args) => Database::with(|db| {
-            db.records
-                .insert(args.username.clone(), md5::compute(args.password).to_vec());
-
-            println!("User {} added to database", args.username);
+            let stored = if args.no_salt {
+                let mut digest = vec![0u8; args.hash.digest_len()];
+                args.hash.hash_into(args.password.as_bytes(), &mut digest);
+                println!(
+                    "User {} added to database, hashed with {:?} (no salt — crackable via gen-htable/gen-rtable)",
+                    args.username, args.hash
+                );
+                StoredHash::Unsalted {
+                    algo: args.hash,
+                    digest,
+                }
+            } else {
+                let mut salt = [0u8; 16];
+                rand::thread_rng().fill_bytes(&mut salt);
+                let hmac = hmac_sha256(&salt, args.password.as_bytes());
+                println!(
+                    "User {} added to database, salted with HMAC-SHA256",
+                    args.username
+                );
+                StoredHash::Salted { salt, hmac }
+            };
+            db.records.insert(args.username.clone(), stored);
             Ok(())
         }),
         Command::ListUsers(_) => Database::with(|db| {
@@ -361,13 +1197,27 @@ fn main() -> Result<(), Box<dyn Error>> {
             Ok(())
         }),
         Command::Auth(args) => Database::with(|db| {
-            let entered = md5::compute(args.password);
             match db.records.get(&args.username) {
-                Some(stored) if stored == entered.as_ref() => {
-                    println!("Authentication successful!");
+                Some(StoredHash::Unsalted { algo, digest }) => {
+                    let mut entered = vec![0u8; algo.digest_len()];
+                    algo.hash_into(args.password.as_bytes(), &mut entered);
+                    if entered == *digest {
+                        println!("Authentication successful!");
+                    } else {
+                        println!("Bad password.");
+                    }
                 }
-                Some(_) => {
-                    println!("Bad password.");
+                Some(StoredHash::Salted { salt, hmac }) => {
+                    let verified = HmacSha256::new_from_slice(salt)
+                        .expect("HMAC accepts a key of any length")
+                        .chain_update(args.password.as_bytes())
+                        .verify_slice(hmac)
+                        .is_ok();
+                    if verified {
+                        println!("Authentication successful!");
+                    } else {
+                        println!("Bad password.");
+                    }
                 }
                 None => {
                     println!("No such user")
@@ -375,8 +1225,390 @@ fn main() -> Result<(), Box<dyn Error>> {
             }
             Ok(())
         }),
-        Command::Bruteforce(_) => bruteforce(),
-        Command::GenHtable(_) => gen_htable(),
+        Command::Bruteforce(args) => bruteforce(args.hash),
+        Command::GenHtable(args) => gen_htable(args.hash),
         Command::UseHtable(_) => use_htable(),
+        Command::GenRtable(args) => gen_rtable(args.hash),
+        Command::UseRtable(_) => use_rtable(),
+        Command::Upgrade(args) => upgrade_table(args),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn reduce_stays_within_keyspace() {
+        let keyspace_size = 37;
+        for position in 0..10 {
+            let hash = [position as u8; 8];
+            assert!(reduce(&hash, position, keyspace_size) < keyspace_size);
+        }
+    }
+
+    #[test]
+    fn reduce_depends_on_position() {
+        let keyspace_size = 1_000_000;
+        let hash = [1, 2, 3, 4, 5, 6, 7, 8];
+        assert_ne!(reduce(&hash, 0, keyspace_size), reduce(&hash, 1, keyspace_size));
+    }
+
+    #[test]
+    fn gen_rtable_then_lookup_rtable_round_trip() {
+        let hash_algo = HashAlgo::Md5;
+        let item_len = 3;
+        let charset: Charset = "ab".into();
+        let keyspace_size = charset.range(item_len).end;
+        let chain_len = 6;
+        // One chain per keyspace item, so every plaintext is some chain's
+        // start and is guaranteed to be found again through its own hash.
+        let num_chains = keyspace_size;
+
+        let chains = generate_chains(
+            hash_algo,
+            &charset,
+            item_len,
+            keyspace_size,
+            chain_len,
+            num_chains,
+            |_| {},
+        );
+
+        let mut buf = vec![0u8; item_len as usize];
+        let mut hash_buf = vec![0u8; hash_algo.digest_len()];
+        let lookup_params = RtableLookupParams {
+            charset: &charset,
+            keyspace_size,
+            chains: &chains,
+            chain_len,
+            hash_algo,
+        };
+
+        for item_index in 0..keyspace_size {
+            charset.get_into(item_index, &mut buf);
+            hash_algo.hash_into(&buf, &mut hash_buf);
+            let expected_password = std::str::from_utf8(&buf).unwrap().to_string();
+            let target_hash = hash_buf.clone();
+
+            let found = lookup_rtable(&target_hash, &lookup_params, &mut buf, &mut hash_buf);
+            assert_eq!(found, Some(expected_password));
+        }
+    }
+
+    #[test]
+    fn chunk_layout_covers_the_whole_keyspace_including_a_trailing_partial_chunk() {
+        let hash_length = 16;
+        let hashes_per_chunk = MAX_CHUNK_BYTES / hash_length as u64;
+        let total_hashes = hashes_per_chunk + 1;
+        let (hashes_per_chunk, bytes_per_chunk, num_chunks) =
+            chunk_layout(total_hashes, hash_length);
+
+        assert_eq!(bytes_per_chunk, hashes_per_chunk * hash_length as u64);
+        assert!(bytes_per_chunk <= MAX_CHUNK_BYTES);
+        // A keyspace one hash past an even chunk boundary still needs a
+        // second chunk, not zero extra chunks from rounding down.
+        assert_eq!(num_chunks, 2);
+        assert!(num_chunks * hashes_per_chunk >= total_hashes);
+    }
+
+    /// Serializes tests that chdir, since the functions under test work
+    /// against fixed relative filenames.
+    static CWD_LOCK: Mutex<()> = Mutex::new(());
+
+    struct TempCwd {
+        original: std::path::PathBuf,
+        dir: std::path::PathBuf,
+        _guard: std::sync::MutexGuard<'static, ()>,
+    }
+
+    impl TempCwd {
+        fn new(name: &str) -> Self {
+            let guard = CWD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            let original = std::env::current_dir().unwrap();
+            let dir = std::env::temp_dir().join(format!("pass-fun-test-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            std::env::set_current_dir(&dir).unwrap();
+            TempCwd { original, dir, _guard: guard }
+        }
+    }
+
+    impl Drop for TempCwd {
+        fn drop(&mut self) {
+            std::env::set_current_dir(&self.original).ok();
+            std::fs::remove_dir_all(&self.dir).ok();
+        }
+    }
+
+    /// Lays out hashes exactly the way `gen_htable` would: one `hash_length`
+    /// stride per item, in keyspace order.
+    fn raw_hash_blob(hash_algo: HashAlgo, charset: &Charset, len: u32) -> Vec<u8> {
+        let total = charset.range(len).end;
+        let hash_length = hash_algo.digest_len();
+        let mut out = vec![0u8; (total * hash_length as u64) as usize];
+        let mut buf = vec![0u8; len as usize];
+        for item_index in 0..total {
+            charset.get_into(item_index, &mut buf);
+            hash_algo.hash_into(&buf, &mut out[(item_index as usize * hash_length)..][..hash_length]);
+        }
+        out
+    }
+
+    #[test]
+    fn table_header_round_trips() {
+        let _cwd = TempCwd::new("header-roundtrip");
+        let header = TableHeader {
+            len: 4,
+            charset: b"ab".to_vec(),
+            hash_algo: HashAlgo::Md5,
+        };
+        let offset_written = {
+            let mut file = File::create("table.db").unwrap();
+            write_table_header(&mut file, &header).unwrap()
+        };
+
+        let mut file = File::open("table.db").unwrap();
+        let (read_back, offset_read) = read_table_header(&mut file).unwrap();
+        assert_eq!(read_back.len, header.len);
+        assert_eq!(read_back.charset, header.charset);
+        assert_eq!(read_back.hash_algo, header.hash_algo);
+        assert_eq!(offset_read, offset_written);
+    }
+
+    #[test]
+    fn table_header_corruption_is_detected() {
+        let _cwd = TempCwd::new("header-corrupt");
+        let header = TableHeader {
+            len: 4,
+            charset: b"ab".to_vec(),
+            hash_algo: HashAlgo::Md5,
+        };
+        {
+            let mut file = File::create("table.db").unwrap();
+            write_table_header(&mut file, &header).unwrap();
+        }
+
+        // Flip a byte inside the bincode-encoded header, just past the
+        // 4-byte magic + 2-byte version + 8-byte length prefix.
+        let mut bytes = std::fs::read("table.db").unwrap();
+        bytes[4 + 2 + 8] ^= 0xff;
+        std::fs::write("table.db", &bytes).unwrap();
+
+        let mut file = File::open("table.db").unwrap();
+        assert!(read_table_header(&mut file).is_err());
+    }
+
+    #[test]
+    fn upgrade_table_no_ops_on_a_current_format_file() {
+        let _cwd = TempCwd::new("upgrade-current");
+        let hash_algo = HashAlgo::Md5;
+        let charset: Charset = "ab".into();
+        let len = 2;
+        let blob = raw_hash_blob(hash_algo, &charset, len);
+        let checksum = crc32fast::hash(&blob);
+
+        {
+            let mut file = File::create("table.db").unwrap();
+            write_table_header(
+                &mut file,
+                &TableHeader {
+                    len,
+                    charset: charset.0.clone(),
+                    hash_algo,
+                },
+            )
+            .unwrap();
+            file.write_all(&blob).unwrap();
+            write_checksum_footer(&mut file, &[checksum]).unwrap();
+        }
+        let before = std::fs::read("table.db").unwrap();
+
+        upgrade_table(Upgrade {
+            len,
+            charset: "ab".into(),
+            hash: hash_algo,
+        })
+        .unwrap();
+
+        let after = std::fs::read("table.db").unwrap();
+        assert_eq!(before, after);
+        assert!(!std::path::Path::new("table.db.upgraded").exists());
+    }
+
+    #[test]
+    fn upgrade_table_migrates_a_legacy_v1_file() {
+        let _cwd = TempCwd::new("upgrade-v1");
+        let hash_algo = HashAlgo::Sha1;
+        let charset: Charset = "xy".into();
+        let len = 2;
+        let header = TableHeader {
+            len,
+            charset: charset.0.clone(),
+            hash_algo,
+        };
+        let blob = raw_hash_blob(hash_algo, &charset, len);
+
+        {
+            let mut file = File::create("table.db").unwrap();
+            file.write_all(TABLE_MAGIC).unwrap();
+            file.write_all(&1u16.to_le_bytes()).unwrap();
+            bincode::serialize_into(&mut file, &header).unwrap();
+            file.write_all(&blob).unwrap();
+        }
+
+        upgrade_table(Upgrade {
+            len,
+            charset: "xy".into(),
+            hash: hash_algo,
+        })
+        .unwrap();
+
+        let mut file = File::open("table.db").unwrap();
+        let (new_header, _offset) = read_table_header(&mut file).unwrap();
+        assert_eq!(new_header.len, len);
+        assert_eq!(new_header.charset, charset.0);
+        assert_eq!(new_header.hash_algo, hash_algo);
+
+        let mut rest = Vec::new();
+        file.read_to_end(&mut rest).unwrap();
+        assert_eq!(&rest[..blob.len()], &blob[..]);
+    }
+
+    #[test]
+    fn upgrade_table_handles_a_fully_headerless_file() {
+        let _cwd = TempCwd::new("upgrade-headerless");
+        let hash_algo = HashAlgo::Md5;
+        let charset_str = "01";
+        let charset: Charset = charset_str.into();
+        let len = 2;
+        let blob = raw_hash_blob(hash_algo, &charset, len);
+
+        std::fs::write("table.db", &blob).unwrap();
+
+        upgrade_table(Upgrade {
+            len,
+            charset: charset_str.into(),
+            hash: hash_algo,
+        })
+        .unwrap();
+
+        let mut file = File::open("table.db").unwrap();
+        let (new_header, _offset) = read_table_header(&mut file).unwrap();
+        assert_eq!(new_header.len, len);
+        assert_eq!(new_header.charset, charset.0);
+        assert_eq!(new_header.hash_algo, hash_algo);
+    }
+
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn hash_into_matches_known_test_vectors() {
+        let cases = [
+            (HashAlgo::Md5, "900150983cd24fb0d6963f7d28e17f72"),
+            (HashAlgo::Sha1, "a9993e364706816aba3e25717850c26c9cd0d89d"),
+            (
+                HashAlgo::Sha256,
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+        ];
+        for (algo, expected_hex) in cases {
+            let mut out = vec![0u8; algo.digest_len()];
+            algo.hash_into(b"abc", &mut out);
+            assert_eq!(to_hex(&out), expected_hex);
+        }
+    }
+
+    #[test]
+    fn xxhash64_digest_len_and_determinism() {
+        let mut a = vec![0u8; HashAlgo::XxHash64.digest_len()];
+        let mut b = vec![0u8; HashAlgo::XxHash64.digest_len()];
+        HashAlgo::XxHash64.hash_into(b"abc", &mut a);
+        HashAlgo::XxHash64.hash_into(b"abc", &mut b);
+        assert_eq!(a, b);
+        assert_eq!(a.len(), 8);
+
+        let mut c = vec![0u8; HashAlgo::XxHash64.digest_len()];
+        HashAlgo::XxHash64.hash_into(b"abcd", &mut c);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn hmac_sha256_round_trips_and_rejects_the_wrong_password() {
+        let salt = [7u8; 16];
+        let hmac = hmac_sha256(&salt, b"correct horse");
+        let stored = StoredHash::Salted { salt, hmac };
+
+        let StoredHash::Salted { salt, hmac } = &stored else {
+            unreachable!()
+        };
+        assert!(HmacSha256::new_from_slice(salt)
+            .unwrap()
+            .chain_update(b"correct horse")
+            .verify_slice(hmac)
+            .is_ok());
+        assert!(HmacSha256::new_from_slice(salt)
+            .unwrap()
+            .chain_update(b"wrong password")
+            .verify_slice(hmac)
+            .is_err());
+    }
+
+    #[test]
+    fn hmac_sha256_is_salt_dependent() {
+        let mac_a = hmac_sha256(&[1u8; 16], b"same password");
+        let mac_b = hmac_sha256(&[2u8; 16], b"same password");
+        assert_ne!(mac_a, mac_b);
+    }
+
+    #[test]
+    fn load_matching_progress_rejects_a_mismatched_fingerprint() {
+        let _cwd = TempCwd::new("progress-fingerprint");
+        let charset: Charset = "ab".into();
+        let checkpoint = GenProgress {
+            len: 3,
+            charset: charset.0.clone(),
+            hash_algo: HashAlgo::Md5,
+            chunk_checksums: vec![0xdead_beef],
+        };
+        save_progress(&checkpoint).unwrap();
+
+        assert!(load_matching_progress(3, &charset, HashAlgo::Md5).is_some());
+        assert!(load_matching_progress(4, &charset, HashAlgo::Md5).is_none());
+        assert!(load_matching_progress(3, &"ba".into(), HashAlgo::Md5).is_none());
+        assert!(load_matching_progress(3, &charset, HashAlgo::Sha1).is_none());
+    }
+
+    #[test]
+    fn gen_htable_resume_skips_completed_chunks_without_duplicating_the_footer() {
+        let _cwd = TempCwd::new("gen-htable-resume");
+        let hash_algo = HashAlgo::Md5;
+        let charset: Charset = "ab".into();
+        let item_len = 2;
+
+        gen_htable_for(hash_algo, item_len, Charset(charset.0.clone())).unwrap();
+        assert!(!std::path::Path::new(PROGRESS_PATH).exists());
+        let first_run = std::fs::read("table.db").unwrap();
+
+        // Simulate a crash that wrote the footer but didn't clean up the
+        // sidecar checkpoint: resuming should treat the table as already
+        // complete rather than appending a second footer on top of the first.
+        let mut file = File::open("table.db").unwrap();
+        let footer = read_checksum_footer(&mut file).unwrap();
+        save_progress(&GenProgress {
+            len: item_len,
+            charset: charset.0.clone(),
+            hash_algo,
+            chunk_checksums: footer,
+        })
+        .unwrap();
+
+        gen_htable_for(hash_algo, item_len, Charset(charset.0.clone())).unwrap();
+
+        assert!(!std::path::Path::new(PROGRESS_PATH).exists());
+        let second_run = std::fs::read("table.db").unwrap();
+        assert_eq!(first_run, second_run);
     }
 }